@@ -0,0 +1,4 @@
+pub mod packet;
+pub mod socket;
+pub mod tcp;
+pub mod tcpflags;
@@ -0,0 +1,128 @@
+use pnet::packet::tcp::{self, MutableTcpPacket, TcpPacket};
+use pnet::packet::Packet;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+const TCP_HEADER_LEN: usize = 20;
+const DEFAULT_WINDOW_SIZE: u16 = 4380;
+
+/// 送信用のTCPセグメントを組み立てる薄いラッパー。`pnet::packet::tcp`の
+/// `MutableTcpPacket`にデータオフセットやウィンドウサイズなど毎回同じ
+/// フィールドを埋める手間を畳み込んでおき、`Socket::send_tcp_packet`から
+/// IPv4/IPv6どちらの疑似ヘッダチェックサムも計算できるようにする。
+#[derive(Clone)]
+pub struct TCPPacket {
+    buffer: Vec<u8>,
+}
+
+impl TCPPacket {
+    pub fn new(payload_len: usize) -> Self {
+        let mut tcp_packet = TCPPacket {
+            buffer: vec![0; TCP_HEADER_LEN + payload_len],
+        };
+        tcp_packet.set_data_offset(5);
+        tcp_packet.set_window_size(DEFAULT_WINDOW_SIZE);
+        tcp_packet
+    }
+
+    pub fn set_src(&mut self, port: u16) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_source(port);
+    }
+
+    pub fn set_dest(&mut self, port: u16) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_destination(port);
+    }
+
+    pub fn set_sequence(&mut self, num: u32) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_sequence(num);
+    }
+
+    pub fn set_acknowledgement(&mut self, num: u32) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_acknowledgement(num);
+    }
+
+    pub fn set_data_offset(&mut self, offset: u8) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_data_offset(offset);
+    }
+
+    pub fn set_flag(&mut self, flag: u8) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_flags(flag);
+    }
+
+    pub fn set_window_size(&mut self, size: u16) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_window(size);
+    }
+
+    pub fn set_payload(&mut self, payload: &[u8]) {
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_payload(payload);
+    }
+
+    /// IPv4の疑似ヘッダを使ってチェックサムを計算し、セットする。
+    pub fn set_checksum_ipv4(&mut self, local_addr: Ipv4Addr, remote_addr: Ipv4Addr) {
+        let checksum = tcp::ipv4_checksum(
+            &TcpPacket::new(&self.buffer).unwrap(),
+            &local_addr,
+            &remote_addr,
+        );
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_checksum(checksum);
+    }
+
+    /// IPv6の疑似ヘッダを使ってチェックサムを計算し、セットする。IPv4と
+    /// 異なりアドレス長と疑似ヘッダの並びが違うため、計算そのものを
+    /// `tcp::ipv6_checksum`に委ねる。
+    pub fn set_checksum_ipv6(&mut self, local_addr: Ipv6Addr, remote_addr: Ipv6Addr) {
+        let checksum = tcp::ipv6_checksum(
+            &TcpPacket::new(&self.buffer).unwrap(),
+            &local_addr,
+            &remote_addr,
+        );
+        MutableTcpPacket::new(&mut self.buffer)
+            .unwrap()
+            .set_checksum(checksum);
+    }
+
+    pub fn packet(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Packet for TCPPacket {
+    fn packet(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    fn payload(&self) -> &[u8] {
+        &self.buffer[TCP_HEADER_LEN..]
+    }
+}
+
+impl fmt::Debug for TCPPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tcp_packet = TcpPacket::new(&self.buffer).unwrap();
+        f.debug_struct("TCPPacket")
+            .field("src", &tcp_packet.get_source())
+            .field("dst", &tcp_packet.get_destination())
+            .field("flag", &tcp_packet.get_flags())
+            .field("seq", &tcp_packet.get_sequence())
+            .field("ack", &tcp_packet.get_acknowledgement())
+            .finish()
+    }
+}
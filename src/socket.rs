@@ -0,0 +1,137 @@
+use crate::packet::TCPPacket;
+use anyhow::{Context, Result};
+use pnet::packet::ip::IpNextHeaderProtocols;
+use pnet::transport::{self, TransportChannelType, TransportSender};
+use std::fmt;
+use std::net::IpAddr;
+
+/// 4-tupleでソケットを一意に識別する。順序は`(ローカルアドレス,
+/// リモートアドレス, ローカルポート, リモートポート)`で、`TCP::dispatch`が
+/// 受信パケットをこの並びと照合して該当ソケットを引く。
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct SockID(pub IpAddr, pub IpAddr, pub u16, pub u16);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TcpStatus {
+    Listen,
+    SynSent,
+    SynRcvd,
+    Established,
+    FinWait1,
+    FinWait2,
+    TimeWait,
+    CloseWait,
+    LastAck,
+    Closing,
+    Closed,
+}
+
+impl fmt::Display for TcpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SendParam {
+    pub initial_seq: u32,
+    pub unpacked_seq: u32,
+    pub next: u32,
+    pub window: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RecvParam {
+    pub initial_seq: u32,
+    pub next: u32,
+    pub window: u16,
+}
+
+pub struct Socket {
+    pub local_addr: IpAddr,
+    pub remote_addr: IpAddr,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub send_param: SendParam,
+    pub recv_param: RecvParam,
+    pub status: TcpStatus,
+    pub recv_buffer: Vec<u8>,
+    sender: TransportSender,
+}
+
+impl Socket {
+    /// `local_addr`と`remote_addr`は同じアドレスファミリでなければならない。
+    /// ファミリに応じてIPv4/TCPまたはIPv6/TCPの`pnet`送信チャネルを開く。
+    pub fn new(
+        local_addr: IpAddr,
+        remote_addr: IpAddr,
+        local_port: u16,
+        remote_port: u16,
+    ) -> Result<Self> {
+        let protocol = match (local_addr, remote_addr) {
+            (IpAddr::V4(_), IpAddr::V4(_)) => {
+                TransportChannelType::Layered(transport::TransportProtocol::Ipv4(
+                    IpNextHeaderProtocols::Tcp,
+                ))
+            }
+            (IpAddr::V6(_), IpAddr::V6(_)) => {
+                TransportChannelType::Layered(transport::TransportProtocol::Ipv6(
+                    IpNextHeaderProtocols::Tcp,
+                ))
+            }
+            _ => anyhow::bail!(
+                "local address {} and remote address {} are not the same address family",
+                local_addr,
+                remote_addr
+            ),
+        };
+        let (sender, _) = transport::transport_channel(65535, protocol)
+            .context("failed to open a raw TCP send socket")?;
+        Ok(Self {
+            local_addr,
+            remote_addr,
+            local_port,
+            remote_port,
+            send_param: SendParam::default(),
+            recv_param: RecvParam::default(),
+            status: TcpStatus::SynSent,
+            recv_buffer: Vec::new(),
+            sender,
+        })
+    }
+
+    pub fn get_sock_id(&self) -> SockID {
+        SockID(self.local_addr, self.remote_addr, self.local_port, self.remote_port)
+    }
+
+    /// TCPセグメントを組み立てて送信する。疑似ヘッダのチェックサムは
+    /// `local_addr`/`remote_addr`のアドレスファミリに応じて計算方法を
+    /// 切り替える。
+    pub fn send_tcp_packet(
+        &mut self,
+        seq: u32,
+        ack: u32,
+        flag: u8,
+        payload: &[u8],
+    ) -> Result<usize> {
+        let mut tcp_packet = TCPPacket::new(payload.len());
+        tcp_packet.set_src(self.local_port);
+        tcp_packet.set_dest(self.remote_port);
+        tcp_packet.set_sequence(seq);
+        tcp_packet.set_acknowledgement(ack);
+        tcp_packet.set_flag(flag);
+        tcp_packet.set_payload(payload);
+        match (self.local_addr, self.remote_addr) {
+            (IpAddr::V4(local), IpAddr::V4(remote)) => {
+                tcp_packet.set_checksum_ipv4(local, remote);
+            }
+            (IpAddr::V6(local), IpAddr::V6(remote)) => {
+                tcp_packet.set_checksum_ipv6(local, remote);
+            }
+            _ => anyhow::bail!("mismatched address families between local and remote addr"),
+        }
+        self.sender
+            .send_to(tcp_packet, self.remote_addr)
+            .context("failed to send a TCP segment")
+    }
+}
@@ -6,22 +6,29 @@ use pnet::packet::{ip::IpNextHeaderProtocols, tcp::TcpPacket, Packet};
 use pnet::transport::{self, TransportChannelType, tcp_packet_iter};
 use rand::{rngs::ThreadRng, Rng};
 use std::collections::HashMap;
-use std::net::{IpAddr, Ipv4Addr};
-use std::process::Command;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddrV4, ToSocketAddrs};
 use std::time::{Duration, SystemTime};
-use std::{cmp, ops::Range, str, thread};
+use std::{ops::Range, thread};
 use std::sync::{RwLock, Condvar, Mutex, Arc};
 
-const UNDETERMINED_IP_ADDR: std::net::Ipv4Addr = Ipv4Addr::new(0,0,0, 0);
-const UNDETERMINED_PORT: u16 = 0;
 const MAX_TRANSMITTING: u8 = 5;
 const RETRANSMITTING_TIMEOUT: u64 = 3;
-const MSS: usize = 1460;
 const PORT_RANGE: Range<u16> = 40000..60000;
+// RFC 6555 Happy Eyeballs: interval between staggered connection attempts.
+const HAPPY_EYEBALLS_DELAY: Duration = Duration::from_millis(250);
+const MAX_CONNECT_ATTEMPTS: usize = 4;
+// verify_reachable must probe a concrete port: dispatch matches replies by
+// their source port, and nothing ever replies from port 0.
+const VERIFY_PROBE_PORT: u16 = 80;
 
 pub struct TCP {
     sockets: RwLock<HashMap<SockID, Socket>>,
-    event_condvar: (Mutex<Option<TCPEvent>>, Condvar),
+    // `SockID` ごとにイベントをキューイングする。単一スロットだと
+    // Happy Eyeballs のように複数のハンドシェイクが並行に進むとき、
+    // 後発の `publish_event` が先発の未消費イベントを踏みつぶして
+    // `wait_event` が取りこぼす恐れがある。
+    event_queue: Mutex<HashMap<SockID, Vec<TCPEventKind>>>,
+    event_cvar: Condvar,
 }
 
 impl TCP {
@@ -29,7 +36,8 @@ impl TCP {
         let sockets = RwLock::new(HashMap::new());
         let tcp = Arc::new(Self {
             sockets,
-            event_condvar: (Mutex::new(None), Condvar::new());
+            event_queue: Mutex::new(HashMap::new()),
+            event_cvar: Condvar::new(),
         });
         let clonned_tcp = tcp.clone();
         std::thread::spawn(move || {
@@ -50,14 +58,27 @@ impl TCP {
         anyhow::bail!("no available port found.");
     }
 
-    pub fn connect(&self, addr: Ipv4Addr, port: u16) -> Result<SockID> {
+    pub fn connect(&self, addr: IpAddr, port: u16) -> Result<SockID> {
+        let sock_id = self.begin_connect(None, addr, port)?;
+        self.wait_event(sock_id, TCPEventKind::ConnectionCompleted);
+        Ok(sock_id)
+    }
+
+    /// ソケットを作り、SYNを送って `sockets` テーブルに登録するところまでを
+    /// 行い、確立を待たずに `SockID` を返す。`connect` はこの直後に
+    /// `wait_event` でブロックするだけだが、`connect_any` は複数の試行の
+    /// `SockID` を先に知っておく必要があるため、このステップを独立させた。
+    ///
+    /// `local_port` に `Some` を渡すとそのポートをそのまま使う(NAT
+    /// ホールパンチングで双方が相手のエンドポイントを予測する
+    /// `connect_reuse` 用)。`None` なら `select_unused_port` で選ぶ。
+    fn begin_connect(&self, local_port: Option<u16>, addr: IpAddr, port: u16) -> Result<SockID> {
         let mut rng = rand::thread_rng();
-        let mut socket = Socket::new(
-            get_source_addr_to(addr)?,
-            addr,
-            self.select_unused_port(&mut rng)?,
-            port,
-        )?;
+        let local_port = match local_port {
+            Some(port) => port,
+            None => self.select_unused_port(&mut rng)?,
+        };
+        let mut socket = Socket::new(get_source_addr_to(addr)?, addr, local_port, port)?;
         socket.send_param.initial_seq = rng.gen_range(1..1 << 31);
         socket.send_tcp_packet(socket.send_param.initial_seq, 0, tcpflags::SYN, &[])?;
         socket.send_param.unpacked_seq = socket.send_param.initial_seq;
@@ -65,26 +86,511 @@ impl TCP {
         let mut table = self.sockets.write().unwrap();
         let sock_id = socket.get_sock_id();
         table.insert(sock_id, socket);
-        drop(table);
+        Ok(sock_id)
+    }
+
+    /// 候補アドレスをアドレスファミリが交互になるよう並べ替える
+    /// (IPv6があれば先頭)。片方のファミリが尽きたらもう片方を詰める。
+    fn interleave_by_family(addrs: &[IpAddr]) -> Vec<IpAddr> {
+        let mut v6: std::collections::VecDeque<IpAddr> =
+            addrs.iter().copied().filter(|a| a.is_ipv6()).collect();
+        let mut v4: std::collections::VecDeque<IpAddr> =
+            addrs.iter().copied().filter(|a| a.is_ipv4()).collect();
+        let mut interleaved = Vec::with_capacity(addrs.len());
+        let mut prefer_v6 = true;
+        while !v6.is_empty() || !v4.is_empty() {
+            let next = if prefer_v6 {
+                v6.pop_front().or_else(|| v4.pop_front())
+            } else {
+                v4.pop_front().or_else(|| v6.pop_front())
+            };
+            if let Some(addr) = next {
+                interleaved.push(addr);
+            }
+            prefer_v6 = !prefer_v6;
+        }
+        interleaved
+    }
+
+    /// RFC 6555 Happy Eyeballs に倣い、候補アドレス群へ向けてずらしながら
+    /// 並行に `connect` を試み、最初に確立した接続を返す。
+    ///
+    /// アドレスファミリが交互になるよう並べ替え(IPv6があれば先頭)、
+    /// `HAPPY_EYEBALLS_DELAY` ごとに次の候補への接続を追加で開始する。
+    /// 各試行は `begin_connect` でSYNを送った直後の `SockID` を
+    /// `started_tx` 経由で即座に報告するので、勝者が決まり次第、
+    /// まだ確立を待っている試行も含め全ての敗者を RST で即座に
+    /// 切断できる(完了を待ってから畳むと、繋がらない試行を
+    /// テーブルに残したままになる)。
+    pub fn connect_any(self: &Arc<Self>, addrs: &[IpAddr], port: u16) -> Result<SockID> {
+        let mut candidates = Self::interleave_by_family(addrs);
+        candidates.truncate(MAX_CONNECT_ATTEMPTS);
+        if candidates.is_empty() {
+            anyhow::bail!("no candidate addresses to connect to");
+        }
+
+        let (started_tx, started_rx) = std::sync::mpsc::channel::<Result<SockID>>();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<SockID>();
+        for (i, addr) in candidates.iter().enumerate() {
+            let tcp = self.clone();
+            let addr = *addr;
+            let started_tx = started_tx.clone();
+            let done_tx = done_tx.clone();
+            thread::spawn(move || {
+                thread::sleep(HAPPY_EYEBALLS_DELAY * i as u32);
+                match tcp.begin_connect(None, addr, port) {
+                    Ok(sock_id) => {
+                        let _ = started_tx.send(Ok(sock_id));
+                        tcp.wait_event(sock_id, TCPEventKind::ConnectionCompleted);
+                        let _ = done_tx.send(sock_id);
+                    }
+                    Err(e) => {
+                        let _ = started_tx.send(Err(e));
+                    }
+                }
+            });
+        }
+        drop(started_tx);
+        drop(done_tx);
+
+        let mut in_flight = Vec::new();
+        for _ in 0..candidates.len() {
+            match started_rx.recv() {
+                Ok(Ok(sock_id)) => in_flight.push(sock_id),
+                Ok(Err(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        if in_flight.is_empty() {
+            anyhow::bail!("all Happy Eyeballs connection attempts failed to start");
+        }
+
+        let deadline = SystemTime::now()
+            + HAPPY_EYEBALLS_DELAY * candidates.len() as u32
+            + Duration::from_secs(RETRANSMITTING_TIMEOUT as u64 * MAX_TRANSMITTING as u64);
+
+        let winner = loop {
+            let remaining = deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::from_millis(0));
+            if remaining.is_zero() {
+                break None;
+            }
+            match done_rx.recv_timeout(remaining) {
+                Ok(sock_id) => break Some(sock_id),
+                Err(_) => break None,
+            }
+        };
+
+        for sock_id in &in_flight {
+            if Some(*sock_id) != winner {
+                self.teardown(*sock_id);
+            }
+        }
+
+        winner.context("all Happy Eyeballs connection attempts failed or timed out")
+    }
+
+    /// ホスト名を受け取り、A/AAAA レコードを引いてから `connect_any` で
+    /// Happy Eyeballs 接続を行う。`std` が採用した `ToSocketAddr` の
+    /// 統一と同じ発想で、呼び出し側は生の `Ipv4Addr` を用意しなくてよい。
+    pub fn connect_to<A: ToSockAddr>(self: &Arc<Self>, a: A) -> Result<SockID> {
+        let candidates = a.to_sock_addrs()?;
+        let port = candidates
+            .first()
+            .map(|(_, port)| *port)
+            .context("no candidate address resolved")?;
+        let addrs: Vec<IpAddr> = candidates.into_iter().map(|(addr, _)| addr).collect();
+        self.connect_any(&addrs, port)
+    }
+
+    /// `select_unused_port` を経由せず、呼び出し側が指定した `local_port` を
+    /// そのまま使って接続を開始する。ランデブーサーバ越しに互いの
+    /// `local_addr` を交換し合うNATホールパンチングでは、双方が相手の
+    /// エンドポイントを事前に予測できる必要があるため、ポートを固定できる
+    /// 接続経路が要る。
+    pub fn connect_reuse(&self, local_port: u16, addr: IpAddr, port: u16) -> Result<SockID> {
+        let sock_id = self.begin_connect(Some(local_port), addr, port)?;
         self.wait_event(sock_id, TCPEventKind::ConnectionCompleted);
         Ok(sock_id)
     }
-}
 
-fn get_source_addr_to(addr: Ipv4Addr) -> Result<Ipv4Addr> {
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg(format!("ip route get {} | grep src", addr))
-        .output()?;
-    let mut output = str::from_utf8(&output.stdout)?
-        .trim()
-        .split_ascii_whitespace();
-    while let Some(s) = output.next() {
-        if s == "src" {
-            break;
+    /// 接続確立レースに敗れた(あるいは中断すべき)ソケットへ RST を送り、
+    /// テーブルから取り除く。まだ `wait_event` でブロックしているスレッドが
+    /// いても取り残されないよう、`ConnectionClosed` を publish して起こす。
+    fn teardown(&self, sock_id: SockID) {
+        let mut table = self.sockets.write().unwrap();
+        if let Some(mut socket) = table.remove(&sock_id) {
+            let _ = socket.send_tcp_packet(
+                socket.send_param.next,
+                socket.recv_param.next,
+                tcpflags::RST,
+                &[],
+            );
+        }
+        drop(table);
+        self.publish_event(sock_id, TCPEventKind::ConnectionClosed);
+    }
+
+    /// IPv4/TCP と IPv6/TCP の2本の生ソケットを開き、両方から届くパケットを
+    /// 同じ `sockets` テーブルへまとめて振り分ける。
+    ///
+    /// `tcp_packet_iter` の `next()` はブロッキングなので、1本のスレッドで
+    /// 両方のイテレータを交互に呼ぶと、先に呼んだ方にパケットが来ない限り
+    /// もう一方には一生たどり着けない(例えばIPv6オンリーの通信はv4側の
+    /// `next()` で永遠に止まる)。そのためファミリごとに専用スレッドを立て、
+    /// それぞれが自分のイテレータをブロッキングで回す。
+    fn receive_handler(self: &Arc<Self>) -> Result<()> {
+        let (_, mut v4_receiver) = transport::transport_channel(
+            65535,
+            TransportChannelType::Layered(transport::TransportProtocol::Ipv4(
+                IpNextHeaderProtocols::Tcp,
+            )),
+        )
+        .context("failed to open IPv4 raw socket")?;
+        let (_, mut v6_receiver) = transport::transport_channel(
+            65535,
+            TransportChannelType::Layered(transport::TransportProtocol::Ipv6(
+                IpNextHeaderProtocols::Tcp,
+            )),
+        )
+        .context("failed to open IPv6 raw socket")?;
+
+        let v4_tcp = self.clone();
+        let v4_handle = thread::spawn(move || {
+            let mut v4_iter = tcp_packet_iter(&mut v4_receiver);
+            loop {
+                if let Ok((packet, src)) = v4_iter.next() {
+                    v4_tcp.dispatch(packet.packet(), src);
+                }
+            }
+        });
+        let v6_tcp = self.clone();
+        let v6_handle = thread::spawn(move || {
+            let mut v6_iter = tcp_packet_iter(&mut v6_receiver);
+            loop {
+                if let Ok((packet, src)) = v6_iter.next() {
+                    v6_tcp.dispatch(packet.packet(), src);
+                }
+            }
+        });
+        let _ = v4_handle.join();
+        let _ = v6_handle.join();
+        Ok(())
+    }
+
+    /// 受信したパケットを、リモートアドレス・ローカルポート・リモートポートの
+    /// 3つで引いた `Socket` の状態機械へ渡す。ローカルアドレスは照合しない。
+    ///
+    /// 通常のアクティブオープン(SYN送信 → SYN/ACK受信 → ACK送信)に加えて、
+    /// NAT越えで双方が同時にSYNを送り合う simultaneous open にも対応する。
+    /// `SYN_SENT` の最中に(SYN/ACKではなく)素のSYNが届いた場合は
+    /// `SYN_RECEIVED` へ遷移してSYN/ACKを返し、クロスしたACKが届いた時点で
+    /// `ESTABLISHED` に収束させる。
+    fn dispatch(&self, packet: &[u8], remote_addr: IpAddr) {
+        let tcp_packet = match TcpPacket::new(packet) {
+            Some(p) => p,
+            None => return,
+        };
+        let flags = tcp_packet.get_flags();
+        let mut table = self.sockets.write().unwrap();
+        let sock_id = match table.keys().find(|k| {
+            k.1 == remote_addr
+                && k.2 == tcp_packet.get_destination()
+                && k.3 == tcp_packet.get_source()
+        }) {
+            Some(id) => *id,
+            None => return,
+        };
+        let mut completed = false;
+        if let Some(socket) = table.get_mut(&sock_id) {
+            match socket.status {
+                TcpStatus::SynSent if flags == tcpflags::SYN => {
+                    // Simultaneous open: 相手からも素のSYNが届いた。
+                    socket.recv_param.next = tcp_packet.get_sequence() + 1;
+                    socket.status = TcpStatus::SynRcvd;
+                    let _ = socket.send_tcp_packet(
+                        socket.send_param.initial_seq,
+                        socket.recv_param.next,
+                        tcpflags::SYN | tcpflags::ACK,
+                        &[],
+                    );
+                }
+                TcpStatus::SynSent if flags == tcpflags::SYN | tcpflags::ACK => {
+                    socket.recv_param.next = tcp_packet.get_sequence() + 1;
+                    socket.send_param.unpacked_seq = tcp_packet.get_acknowledgement();
+                    let _ = socket.send_tcp_packet(
+                        socket.send_param.next,
+                        socket.recv_param.next,
+                        tcpflags::ACK,
+                        &[],
+                    );
+                    socket.status = TcpStatus::Established;
+                    completed = true;
+                }
+                TcpStatus::SynRcvd if flags & tcpflags::ACK != 0 => {
+                    socket.status = TcpStatus::Established;
+                    completed = true;
+                }
+                _ => {
+                    socket.recv_buffer.extend_from_slice(tcp_packet.payload());
+                }
+            }
+        }
+        drop(table);
+        if completed {
+            self.publish_event(sock_id, TCPEventKind::ConnectionCompleted);
+        } else {
+            self.publish_event(sock_id, TCPEventKind::DataArrived);
+        }
+    }
+
+    /// `sock_id` 宛に望みの種類のイベントが届くまで待つ。`ConnectionClosed`
+    /// が先に届いた場合は、`teardown` によって待ち先のソケットごと
+    /// 切断されたということなので、待ち続けて取り残されないようそちらで
+    /// 抜ける。
+    fn wait_event(&self, sock_id: SockID, kind: TCPEventKind) {
+        let mut queue = self.event_queue.lock().unwrap();
+        loop {
+            if let Some(events) = queue.get_mut(&sock_id) {
+                if let Some(pos) = events.iter().position(|k| *k == kind) {
+                    events.remove(pos);
+                    return;
+                }
+                if kind != TCPEventKind::ConnectionClosed {
+                    if let Some(pos) = events
+                        .iter()
+                        .position(|k| *k == TCPEventKind::ConnectionClosed)
+                    {
+                        events.remove(pos);
+                        return;
+                    }
+                }
+            }
+            queue = self.event_cvar.wait(queue).unwrap();
+        }
+    }
+
+    /// `wait_event` のタイムアウト付き版。特定の種類を待たず、この
+    /// `sock_id` 宛のイベントが1つでも届けば成功とみなす。`verify_reachable`
+    /// のように「何か返ってきたか」だけを知りたい場合に使う。
+    fn wait_any_event_timeout(&self, sock_id: SockID, timeout: Duration) -> bool {
+        let mut queue = self.event_queue.lock().unwrap();
+        let deadline = SystemTime::now() + timeout;
+        loop {
+            if let Some(events) = queue.get_mut(&sock_id) {
+                if !events.is_empty() {
+                    events.clear();
+                    return true;
+                }
+            }
+            let remaining = deadline
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::from_millis(0));
+            if remaining.is_zero() {
+                return false;
+            }
+            let (new_queue, result) = self.event_cvar.wait_timeout(queue, remaining).unwrap();
+            queue = new_queue;
+            if result.timed_out() {
+                return false;
+            }
+        }
+    }
+
+    /// 実トラフィックを流す前に、生ソケット経路が本当に疎通しているかを
+    /// 確認する。`select_unused_port` で取ったポートから `VERIFY_PROBE_PORT`
+    /// 宛にプローブ用のSYNを送り、`receive_handler` スレッドが
+    /// `tcp_packet_iter` 越しに何らかの返信を観測できるかを、有限の
+    /// 待ち時間で検証する。
+    ///
+    /// ここで失敗するのはだいたい `CAP_NET_RAW` が無い、`get_source_addr_to`
+    /// のUDP connect probeに対してカーネルが経路/送信元アドレスを
+    /// 持っていない、40000..60000 番のポートがファイアウォールで
+    /// 落とされている、のいずれかであり、`wait_event` の中で無言のまま
+    /// ハングするより先に分かりやすいエラーとして突き返す。
+    pub fn verify_reachable(&self, probe_addr: IpAddr) -> Result<()> {
+        let mut rng = rand::thread_rng();
+        let local_port = self.select_unused_port(&mut rng)?;
+        let local_addr = get_source_addr_to(probe_addr).context(
+            "could not determine a source address for the probe; \
+             the kernel has no route/source address for this destination",
+        )?;
+        let mut socket = Socket::new(local_addr, probe_addr, local_port, VERIFY_PROBE_PORT)?;
+        socket.send_param.initial_seq = rng.gen_range(1..1 << 31);
+        socket
+            .send_tcp_packet(socket.send_param.initial_seq, 0, tcpflags::SYN, &[])
+            .context("failed to send a probe SYN; confirm the process has CAP_NET_RAW")?;
+        socket.send_param.unpacked_seq = socket.send_param.initial_seq;
+        socket.send_param.next = socket.send_param.initial_seq + 1;
+        let sock_id = socket.get_sock_id();
+        let mut table = self.sockets.write().unwrap();
+        table.insert(sock_id, socket);
+        drop(table);
+
+        let reachable =
+            self.wait_any_event_timeout(sock_id, Duration::from_secs(RETRANSMITTING_TIMEOUT));
+        self.teardown(sock_id);
+        if !reachable {
+            anyhow::bail!(
+                "no reply observed for probe to {} within {}s; check CAP_NET_RAW, \
+                 that the kernel has a route/source address for this destination, \
+                 and that the {}..{} port range is not firewalled",
+                probe_addr,
+                RETRANSMITTING_TIMEOUT,
+                PORT_RANGE.start,
+                PORT_RANGE.end
+            );
         }
+        Ok(())
+    }
+
+    fn publish_event(&self, sock_id: SockID, kind: TCPEventKind) {
+        let mut queue = self.event_queue.lock().unwrap();
+        queue.entry(sock_id).or_default().push(kind);
+        self.event_cvar.notify_all();
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum TCPEventKind {
+    ConnectionCompleted,
+    DataArrived,
+    ConnectionClosed,
+}
+
+/// `std::net::ToSocketAddrs` と同じ役割を `connect_to` 向けに持たせるための
+/// トレイト。こちらは `(IpAddr, u16)` の候補リストへ解決する点が異なり、
+/// Happy Eyeballs が複数アドレスを同時に試せるようにする。
+pub trait ToSockAddr {
+    fn to_sock_addrs(&self) -> Result<Vec<(IpAddr, u16)>>;
+}
+
+impl ToSockAddr for SocketAddrV4 {
+    fn to_sock_addrs(&self) -> Result<Vec<(IpAddr, u16)>> {
+        Ok(vec![(IpAddr::V4(*self.ip()), self.port())])
+    }
+}
+
+impl ToSockAddr for (Ipv4Addr, u16) {
+    fn to_sock_addrs(&self) -> Result<Vec<(IpAddr, u16)>> {
+        Ok(vec![(IpAddr::V4(self.0), self.1)])
+    }
+}
+
+impl ToSockAddr for (&str, u16) {
+    fn to_sock_addrs(&self) -> Result<Vec<(IpAddr, u16)>> {
+        let addrs = (self.0, self.1)
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve host {}", self.0))?;
+        Ok(addrs.map(|a| (a.ip(), a.port())).collect())
     }
-    let ip = output.next().context("failed to get src ip")?;
-    dbg!("source addr", ip);
-    ip.parse().context("failed to parse source ip")
-}
\ No newline at end of file
+}
+
+impl ToSockAddr for &str {
+    fn to_sock_addrs(&self) -> Result<Vec<(IpAddr, u16)>> {
+        let addrs = self
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve \"{}\"", self))?;
+        Ok(addrs.map(|a| (a.ip(), a.port())).collect())
+    }
+}
+
+// `connect` 先へ実際にパケットを送らずとも、送信に使う送信元アドレスは
+// カーネルの経路表から決まる。宛先へ`connect`しただけの(何も送信しない)
+// UDPソケットを作り、`local_addr`を読み返すことでそれを学習する。
+// `ip route get`を`sh`経由で叩いていた旧実装は、ロケール依存のテキスト
+// 解析が必要でIPv6にも対応できず、最小限のコンテナでは失敗していた。
+const ROUTE_PROBE_PORT: u16 = 80;
+
+fn get_source_addr_to(addr: IpAddr) -> Result<IpAddr> {
+    let bind_addr: IpAddr = match addr {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let probe = std::net::UdpSocket::bind((bind_addr, 0))
+        .context("failed to open a probe UDP socket")?;
+    probe
+        .connect((addr, ROUTE_PROBE_PORT))
+        .context("failed to resolve a route to the destination")?;
+    probe
+        .local_addr()
+        .map(|a| a.ip())
+        .context("failed to read back the kernel-chosen source address")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interleave_by_family_alternates_starting_with_ipv6() {
+        let v4a: IpAddr = Ipv4Addr::new(192, 0, 2, 1).into();
+        let v4b: IpAddr = Ipv4Addr::new(192, 0, 2, 2).into();
+        let v6a: IpAddr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into();
+        let v6b: IpAddr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2).into();
+
+        let interleaved = TCP::interleave_by_family(&[v4a, v4b, v6a, v6b]);
+        assert_eq!(interleaved, vec![v6a, v4a, v6b, v4b]);
+    }
+
+    #[test]
+    fn interleave_by_family_drains_remaining_family_once_other_runs_out() {
+        let v4a: IpAddr = Ipv4Addr::new(192, 0, 2, 1).into();
+        let v4b: IpAddr = Ipv4Addr::new(192, 0, 2, 2).into();
+        let v6a: IpAddr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).into();
+
+        let interleaved = TCP::interleave_by_family(&[v4a, v6a, v4b]);
+        assert_eq!(interleaved, vec![v6a, v4a, v4b]);
+    }
+
+    #[test]
+    fn interleave_by_family_empty_input_is_empty() {
+        assert!(TCP::interleave_by_family(&[]).is_empty());
+    }
+
+    #[test]
+    fn to_sock_addr_socket_addr_v4() {
+        let addr = SocketAddrV4::new(Ipv4Addr::new(203, 0, 113, 1), 443);
+        let resolved = addr.to_sock_addrs().unwrap();
+        assert_eq!(
+            resolved,
+            vec![(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1)), 443)]
+        );
+    }
+
+    #[test]
+    fn to_sock_addr_ipv4_port_tuple() {
+        let resolved = (Ipv4Addr::new(203, 0, 113, 2), 8080)
+            .to_sock_addrs()
+            .unwrap();
+        assert_eq!(
+            resolved,
+            vec![(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 2)), 8080)]
+        );
+    }
+
+    #[test]
+    fn to_sock_addr_str_port_tuple_resolves_numeric_host() {
+        let resolved = ("127.0.0.1", 80).to_sock_addrs().unwrap();
+        assert_eq!(resolved, vec![(IpAddr::V4(Ipv4Addr::LOCALHOST), 80)]);
+    }
+
+    #[test]
+    fn to_sock_addr_str_resolves_numeric_host_port() {
+        let resolved = "127.0.0.1:80".to_sock_addrs().unwrap();
+        assert_eq!(resolved, vec![(IpAddr::V4(Ipv4Addr::LOCALHOST), 80)]);
+    }
+
+    #[test]
+    fn get_source_addr_to_picks_loopback_for_loopback_destination_v4() {
+        let src = get_source_addr_to(IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap();
+        assert_eq!(src, IpAddr::V4(Ipv4Addr::LOCALHOST));
+    }
+
+    #[test]
+    fn get_source_addr_to_picks_loopback_for_loopback_destination_v6() {
+        let src = get_source_addr_to(IpAddr::V6(Ipv6Addr::LOCALHOST)).unwrap();
+        assert_eq!(src, IpAddr::V6(Ipv6Addr::LOCALHOST));
+    }
+}
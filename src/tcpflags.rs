@@ -0,0 +1,8 @@
+pub const CWR: u8 = 1 << 7;
+pub const ECE: u8 = 1 << 6;
+pub const URG: u8 = 1 << 5;
+pub const ACK: u8 = 1 << 4;
+pub const PSH: u8 = 1 << 3;
+pub const RST: u8 = 1 << 2;
+pub const SYN: u8 = 1 << 1;
+pub const FIN: u8 = 1;